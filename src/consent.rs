@@ -0,0 +1,150 @@
+//! Tracks whether the user has consented to metrics collection and the
+//! telemetry identifier recorded alongside it, persisting both across
+//! restarts so a prior opt-out is honored the next time the controller is
+//! constructed.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use rand::Rng;
+use rand::os::OsRng;
+
+/// Default on-disk location of the consent/identifier state, scoped to the
+/// host application's identity so two apps sharing a working directory
+/// don't read and clobber each other's consent state.
+pub fn default_path(app_name: &str) -> PathBuf {
+    PathBuf::from(format!("metrics_consent_{}.txt", sanitize(app_name)))
+}
+
+/// Replaces anything that isn't alphanumeric, `-` or `_` with `_`, so an
+/// app name can be safely embedded in a filename.
+fn sanitize(component: &str) -> String {
+    component.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// Whether the controller is active, and the identifier it should tag
+/// recorded events with.
+pub struct Consent {
+    pub active: bool,
+    pub client_id: String
+}
+
+impl Consent {
+    pub fn new(active: bool, client_id: String) -> Consent {
+        Consent {
+            active: active,
+            client_id: client_id
+        }
+    }
+}
+
+/// Generates a fresh telemetry identifier: 128 bits drawn from the OS
+/// CSPRNG. Not derived from anything -- time, a counter, or otherwise --
+/// that could tie it back to a previous identifier.
+pub fn generate_client_id() -> String {
+    let mut rng = OsRng::new().expect("failed to initialize OS random number generator");
+    let bytes: [u8; 16] = rng.gen();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Atomically writes the consent state to `path`.
+pub fn save(consent: &Consent, path: &Path) -> io::Result<()> {
+    let contents = format!("{}\t{}\n", consent.active, consent.client_id);
+
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut file = try!(File::create(&tmp_path));
+        try!(file.write_all(contents.as_bytes()));
+    }
+    fs::rename(&tmp_path, path)
+}
+
+/// Reads back the consent state, if any has been persisted. A missing
+/// file means nothing has ever been recorded, so the caller is free to
+/// default to active -- but a file that exists and fails to parse is
+/// treated as an opt-out, never as "nothing recorded", so a transient
+/// read error or a corrupted file can't silently re-enable collection
+/// for a user who previously opted out.
+pub fn load(path: &Path) -> io::Result<Option<Consent>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut file = try!(File::open(path));
+    let mut contents = String::new();
+    try!(file.read_to_string(&mut contents));
+
+    let fields: Vec<&str> = contents.trim().split('\t').collect();
+    if fields.len() != 2 {
+        return Ok(Some(Consent::new(false, generate_client_id())));
+    }
+    let active = fields[0].parse().unwrap_or(false);
+    Ok(Some(Consent::new(active, fields[1].to_string())))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{default_path, generate_client_id, load, save, Consent};
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let mut path = ::std::env::temp_dir();
+        path.push(format!("metrics_controller_consent_test_{}_{}.txt", name, generate_client_id()));
+        path
+    }
+
+    #[test]
+    fn save_then_load_round_trips_active_flag_and_client_id() {
+        let path = temp_path("round_trip");
+        let consent = Consent::new(false, "abc123".to_string());
+
+        save(&consent, &path).unwrap();
+        let restored = load(&path).unwrap().expect("consent file was just written");
+
+        assert_eq!(restored.active, false);
+        assert_eq!(restored.client_id, "abc123");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn load_returns_none_when_nothing_has_been_persisted() {
+        let path = temp_path("missing");
+        assert!(load(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn load_fails_closed_on_a_corrupted_file() {
+        let path = temp_path("corrupted");
+        fs::write(&path, "not a valid consent row").unwrap();
+
+        let restored = load(&path).unwrap().expect("a corrupted file still yields a consent");
+        assert_eq!(restored.active, false);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn load_fails_closed_on_an_unparseable_active_flag() {
+        let path = temp_path("garbled_flag");
+        fs::write(&path, "maybe\tabc123\n").unwrap();
+
+        let restored = load(&path).unwrap().expect("a garbled flag still yields a consent");
+        assert_eq!(restored.active, false);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn generate_client_id_rotates_to_a_different_identifier_each_call() {
+        assert!(generate_client_id() != generate_client_id());
+    }
+
+    #[test]
+    fn default_path_scopes_by_app_name() {
+        assert!(default_path("app-one") != default_path("app-two"));
+    }
+}
@@ -1,9 +1,13 @@
 use metrics_worker::MetricsWorker;
-use events::Events;
+use events::{Events, Label, Sample};
 use log::LogLevelFilter;
 use logger::MetricsLoggerFactory;
 use logger::MetricsLogger;
-use std::sync::{Arc, Mutex};
+use consent::{self, Consent};
+use persistence;
+use transmit::TransmitConfig;
+use crossbeam_channel::{self, Sender};
+use std::path::PathBuf;
 
 #[allow(non_upper_case_globals)]
 // Shortcut to MetricsLoggerFactory function that gets the logger instance.
@@ -60,9 +64,17 @@ impl EventInfo {
 
 /// The metrics controller for the CD Metrics Library
 pub struct MetricsController {
-    #[allow(dead_code)] // Issue #33 -- Will go away with subsequent commits.
-    ev: Arc<Mutex<Events>>,
-    mw: MetricsWorker
+    // The only thing shared with the worker thread: a multi-producer
+    // channel of samples. Recording never blocks on the worker aggregating,
+    // persisting or transmitting.
+    samples: Option<Sender<Sample>>,
+    mw: Option<MetricsWorker>,
+    event_info: EventInfo,
+    transmit_config: TransmitConfig,
+    client_id: String,
+    active: bool,
+    persistence_path: PathBuf,
+    consent_path: PathBuf
 
 }
 
@@ -80,8 +92,8 @@ impl MetricsController {
     ///
     /// ```ignore
     /// use metrics_controller::controller::MetricsController;
+    /// use metrics_controller::transmit::TransmitConfig;
     /// let mc = MetricsController::new(
-    ///     true,
     ///     "foxbox".to_string(),
     ///     "1.0".to_string(),
     ///     "beta".to_string(),
@@ -89,73 +101,278 @@ impl MetricsController {
     ///     "rust".to_string(),
     ///     "en-us".to_string(),
     ///     "RPi2".to_string(),
-    ///     "arm".to_string());
+    ///     "arm".to_string(),
+    ///     "linux".to_string(),
+    ///     "1.2.3".to_string(),
+    ///     TransmitConfig::new("https://telemetry.example.com/collect".to_string()));
     /// ```
     pub fn new(app_name: String, app_version: String,
                app_update_channel: String, app_build_id: String,
                app_platform: String, locale: String,
-               device: String, arch: String, os: String, os_version: String) -> MetricsController {
+               device: String, arch: String, os: String, os_version: String,
+               transmit_config: TransmitConfig) -> MetricsController {
         logger().log(LogLevelFilter::Info, "Creating Controller");
         let event_info = EventInfo::new(
                     locale,
+                    os,
+                    os_version,
                     device,
                     app_name,
                     app_version,
                     app_update_channel,
                     app_build_id,
                     app_platform,
-                    arch,
-                    os,
-                    os_version);
-        let events = Arc::new(Mutex::new(Events::new(event_info)));
+                    arch);
+        let persistence_path = persistence::default_path(&event_info.app_name, &event_info.app_build_id);
+        let consent_path = consent::default_path(&event_info.app_name);
+
+        // A prior opt-out is honored across restarts: if none was recorded,
+        // default to active with a fresh identifier.
+        let consent = match consent::load(&consent_path) {
+            Ok(Some(consent)) => consent,
+            Ok(None) => {
+                let fresh = Consent::new(true, consent::generate_client_id());
+                if let Err(e) = consent::save(&fresh, &consent_path) {
+                    logger().log(LogLevelFilter::Warn, &format!("Failed to persist consent state: {}", e));
+                }
+                fresh
+            }
+            Err(e) => {
+                logger().log(LogLevelFilter::Warn,
+                              &format!("Failed to load consent state, defaulting to opted-out: {}", e));
+                Consent::new(false, consent::generate_client_id())
+            }
+        };
+
+        let mut controller = MetricsController {
+            samples: None,
+            mw: None,
+            event_info: event_info,
+            transmit_config: transmit_config,
+            client_id: consent.client_id,
+            active: false,
+            persistence_path: persistence_path,
+            consent_path: consent_path
+        };
 
-        MetricsController {
-            ev: events.clone(),
-            mw: MetricsWorker::new(events)
+        if consent.active {
+            controller.start_collecting();
         }
+
+        controller
+    }
+
+    /// Restores persisted metrics (if any) and spawns the worker thread,
+    /// which becomes the exclusive owner of the aggregated `Events`.
+    fn start_collecting(&mut self) {
+        let events = match persistence::load(self.event_info.clone(), self.client_id.clone(), &self.persistence_path) {
+            Ok(Some(restored)) => restored,
+            Ok(None) => Events::new(self.event_info.clone(), self.client_id.clone()),
+            Err(e) => {
+                logger().log(LogLevelFilter::Warn,
+                              &format!("Failed to load persisted metrics, starting fresh: {}", e));
+                Events::new(self.event_info.clone(), self.client_id.clone())
+            }
+        };
+
+        let (samples_tx, samples_rx) = crossbeam_channel::unbounded();
+
+        self.samples = Some(samples_tx);
+        self.mw = Some(MetricsWorker::new(events, samples_rx, self.persistence_path.clone(),
+                                           self.transmit_config.clone()));
+        self.active = true;
     }
 
     /// This function is called to start the metrics service.  It also starts the
     /// worker thread needed to operate the metrics service.  The worker thread
     /// is responsible for periodically: persisting the histogram data and
     /// transmitting it to the telemetry server.
+    ///
+    /// The worker thread is in fact started, and the histogram data loaded
+    /// from disk, by `new` (when consent is active) and by `opt_in`; this
+    /// function does nothing further and always returns `true`.
     pub fn start_metrics(&mut self) -> bool {
-
-        //Data needs to be read from disk here.  Let's assume that the controller
-        //owns the histogram data for now.
-        // Needs to call persistence module to read the data file.
-        // Call config.init()
-        // Call persistence.read() and populate histograms in memory in controller.
-        // histograms in separate structs in separate files.  Controller maintains
-        // a refernce to the in memory histograms.  Worker thread also needs it.
-        // We would prefer to use a singleton pattern.
-        //MetricsWorker::new();
         true
     }
 
+    /// Pushes a sample onto the channel the worker drains on its next
+    /// flush tick. A no-op if the user has opted out or the channel has no
+    /// consumer (the send simply has nowhere to go).
+    fn record(&self, sample: Sample) {
+        if !self.active {
+            return;
+        }
+        if let Some(ref samples) = self.samples {
+            let _ = samples.send(sample);
+        }
+    }
+
+    /// Records an increment to a monotonically increasing counter metric.
+    /// A no-op if the user has opted out.
+    pub fn record_counter(&self, name: &str, labels: &[Label], value: u64) {
+        self.record(Sample::Counter { name: name.to_string(), labels: labels.to_vec(), value: value });
+    }
+
+    /// Records the latest value of a gauge metric. A no-op if the user has
+    /// opted out.
+    pub fn record_gauge(&self, name: &str, labels: &[Label], value: f64) {
+        self.record(Sample::Gauge { name: name.to_string(), labels: labels.to_vec(), value: value });
+    }
+
+    /// Records a sample observation into a histogram metric. A no-op if the
+    /// user has opted out.
+    pub fn record_histogram(&self, name: &str, labels: &[Label], value: u64) {
+        self.record(Sample::Histogram { name: name.to_string(), labels: labels.to_vec(), value: value });
+    }
+
+    /// Registers explicit bucket upper bounds and the quantiles to compute
+    /// for the histogram metric `name`, in place of the default logarithmic
+    /// bucketing. Must be called before the first `record_histogram` call
+    /// for `name` to take effect.
+    pub fn configure_histogram(&self, name: &str, buckets: Vec<u64>, quantiles: Vec<f64>) {
+        self.record(Sample::ConfigureHistogram { name: name.to_string(), buckets: buckets, quantiles: quantiles });
+    }
+
     /// Stops the metrics service and deletes metrics data that has been collected
     /// but not sent to the server.
     pub fn stop_collecting(&mut self) {
-        // TODO:  Eventually, this API will need to also delete the Histograms
-        // from memory and delete the ones on disk.
-        self.mw.quit();
+        if let Some(mut mw) = self.mw.take() {
+            mw.quit();
+        }
+        // The worker thread owned the aggregated Events exclusively; once
+        // it exits, whatever it hadn't flushed yet goes with it.
+        self.samples = None;
+        self.active = false;
+        if let Err(e) = persistence::delete(&self.persistence_path) {
+            logger().log(LogLevelFilter::Warn,
+                          &format!("Failed to delete persisted metrics: {}", e));
+        }
+    }
+
+    /// Opts out of metrics collection: stops the worker, discards whatever
+    /// has been collected (in memory and on disk), and rotates the
+    /// telemetry identifier so that if the user opts back in, future data
+    /// can't be correlated with anything sent before the opt-out.
+    pub fn opt_out(&mut self) {
+        self.stop_collecting();
+
+        self.client_id = consent::generate_client_id();
+        let consent = Consent::new(false, self.client_id.clone());
+        if let Err(e) = consent::save(&consent, &self.consent_path) {
+            logger().log(LogLevelFilter::Warn, &format!("Failed to persist consent state: {}", e));
+        }
+    }
+
+    /// Opts back in to metrics collection: generates a fresh telemetry
+    /// identifier and restarts collection.
+    pub fn opt_in(&mut self) {
+        self.client_id = consent::generate_client_id();
+        let consent = Consent::new(true, self.client_id.clone());
+        if let Err(e) = consent::save(&consent, &self.consent_path) {
+            logger().log(LogLevelFilter::Warn, &format!("Failed to persist consent state: {}", e));
+        }
+
+        self.start_collecting();
     }
 }
 
-// Create a MetricsController with predefined values
-// for unit testing.
+// Create a MetricsController with predefined values for unit testing.
+// `app_build_id` is taken by the caller so each test gets a persisted
+// metrics file of its own (that file is scoped by app name and build id).
+// The consent file is scoped by app name alone, so every test here, all
+// using the app name "app", shares a single consent file.
 #[cfg(test)]
-fn create_metrics_controller() -> MetricsController {
+fn create_metrics_controller(app_build_id: &str) -> MetricsController {
     MetricsController::new(
         "app".to_string(),
         "1.0".to_string(),
         "default".to_string(),
-        "20160305".to_string(),
+        app_build_id.to_string(),
         "rust".to_string(),
         "en-us".to_string(),
         "linux".to_string(),
         "1.2.3".to_string(),
         "raspberry-pi".to_string(),
-        "arm".to_string()
+        "arm".to_string(),
+        ::transmit::TransmitConfig::new("https://telemetry.example.com/collect".to_string())
     )
 }
+
+#[cfg(test)]
+mod test {
+    use super::create_metrics_controller;
+    use consent;
+    use events::Events;
+    use persistence;
+    use std::fs;
+
+    fn cleanup(app_build_id: &str) {
+        let _ = fs::remove_file(persistence::default_path("app", app_build_id));
+        let _ = fs::remove_file(consent::default_path("app"));
+    }
+
+    #[test]
+    fn opt_out_then_a_fresh_controller_comes_up_inactive() {
+        let build_id = "test-opt-out-honored-on-restart";
+        cleanup(build_id);
+
+        let mut mc = create_metrics_controller(build_id);
+        mc.opt_out();
+
+        // Simulate a process restart: a new controller for the same app
+        // identity should come up inactive, honoring the opt-out.
+        let restarted = create_metrics_controller(build_id);
+        assert_eq!(restarted.active, false);
+
+        cleanup(build_id);
+    }
+
+    #[test]
+    fn opt_in_after_opt_out_restarts_collection() {
+        let build_id = "test-opt-in-restarts-collection";
+        cleanup(build_id);
+
+        let mut mc = create_metrics_controller(build_id);
+        mc.opt_out();
+        assert_eq!(mc.active, false);
+
+        mc.opt_in();
+        assert_eq!(mc.active, true);
+        assert!(mc.samples.is_some());
+
+        mc.stop_collecting();
+        cleanup(build_id);
+    }
+
+    #[test]
+    fn opt_out_deletes_the_persisted_metrics_file() {
+        let build_id = "test-opt-out-deletes-persisted-file";
+        cleanup(build_id);
+
+        let mut mc = create_metrics_controller(build_id);
+        persistence::save(&Events::new(mc.event_info.clone(), mc.client_id.clone()), &mc.persistence_path).unwrap();
+        assert!(mc.persistence_path.exists());
+
+        mc.opt_out();
+        assert!(!mc.persistence_path.exists());
+
+        cleanup(build_id);
+    }
+
+    #[test]
+    fn record_counter_is_a_no_op_while_inactive() {
+        let build_id = "test-record-is-a-no-op-while-inactive";
+        cleanup(build_id);
+
+        let mut mc = create_metrics_controller(build_id);
+        mc.opt_out();
+        assert_eq!(mc.active, false);
+
+        // Recording while inactive must not panic or resurrect the
+        // channel/worker.
+        mc.record_counter("requests", &[], 1);
+        assert!(mc.samples.is_none());
+
+        cleanup(build_id);
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use histogram::Histogram;
+use controller::EventInfo;
+
+/// A single label attached to a metric, e.g. `("method", "GET")`.
+pub type Label = (String, String);
+
+/// A recording sent from an application thread to the worker over the
+/// lock-free sample channel. The worker is the only thing that ever reads
+/// these, applying each one to its exclusively-owned `Events` on the next
+/// flush tick.
+pub enum Sample {
+    Counter { name: String, labels: Vec<Label>, value: u64 },
+    Gauge { name: String, labels: Vec<Label>, value: f64 },
+    Histogram { name: String, labels: Vec<Label>, value: u64 },
+    ConfigureHistogram { name: String, buckets: Vec<u64>, quantiles: Vec<f64> }
+}
+
+/// A monotonically increasing counter.
+pub struct Counter {
+    pub value: u64
+}
+
+impl Counter {
+    pub fn new() -> Counter {
+        Counter { value: 0 }
+    }
+
+    pub fn from_value(value: u64) -> Counter {
+        Counter { value: value }
+    }
+
+    pub fn increment(&mut self, amount: u64) {
+        self.value += amount;
+    }
+}
+
+/// Holds the single latest value reported for a metric.
+pub struct Gauge {
+    pub value: f64
+}
+
+impl Gauge {
+    pub fn new() -> Gauge {
+        Gauge { value: 0.0 }
+    }
+
+    pub fn from_value(value: f64) -> Gauge {
+        Gauge { value: value }
+    }
+
+    pub fn set(&mut self, value: f64) {
+        self.value = value;
+    }
+}
+
+/// In-memory store of everything collected since the last flush: the static
+/// information about the running application plus the counters, gauges and
+/// histograms recorded against it.
+pub struct Events {
+    #[allow(dead_code)]
+    info: EventInfo,
+    client_id: String,
+    counters: HashMap<String, Counter>,
+    gauges: HashMap<String, Gauge>,
+    histograms: HashMap<String, Histogram>,
+    histogram_configs: HashMap<String, (Vec<u64>, Vec<f64>)>
+}
+
+impl Events {
+    pub fn new(info: EventInfo, client_id: String) -> Events {
+        Events {
+            info: info,
+            client_id: client_id,
+            counters: HashMap::new(),
+            gauges: HashMap::new(),
+            histograms: HashMap::new(),
+            histogram_configs: HashMap::new()
+        }
+    }
+
+    /// Rebuilds an `Events` store from previously persisted metric state.
+    pub fn from_parts(info: EventInfo, client_id: String, counters: HashMap<String, Counter>,
+                       gauges: HashMap<String, Gauge>, histograms: HashMap<String, Histogram>,
+                       histogram_configs: HashMap<String, (Vec<u64>, Vec<f64>)>) -> Events {
+        Events {
+            info: info,
+            client_id: client_id,
+            counters: counters,
+            gauges: gauges,
+            histograms: histograms,
+            histogram_configs: histogram_configs
+        }
+    }
+
+    /// Registers explicit bucket upper bounds and the quantiles to compute
+    /// for the histogram metric `name`, used the next time it's recorded
+    /// into. Has no effect on a histogram already created for this name.
+    pub fn configure_histogram(&mut self, name: &str, buckets: Vec<u64>, quantiles: Vec<f64>) {
+        self.histogram_configs.insert(name.to_string(), (buckets, quantiles));
+    }
+
+    /// Builds the flat key used to index a metric, folding the label set
+    /// into the name so that the same metric name with different labels is
+    /// tracked independently.
+    fn metric_key(name: &str, labels: &[Label]) -> String {
+        let mut sorted_labels = labels.to_vec();
+        sorted_labels.sort();
+        let mut key = String::from(name);
+        for &(ref label_name, ref label_value) in &sorted_labels {
+            key.push('|');
+            key.push_str(label_name);
+            key.push('=');
+            key.push_str(label_value);
+        }
+        key
+    }
+
+    pub fn record_counter(&mut self, name: &str, labels: &[Label], value: u64) {
+        let key = Events::metric_key(name, labels);
+        self.counters.entry(key).or_insert_with(Counter::new).increment(value);
+    }
+
+    pub fn record_gauge(&mut self, name: &str, labels: &[Label], value: f64) {
+        let key = Events::metric_key(name, labels);
+        self.gauges.entry(key).or_insert_with(Gauge::new).set(value);
+    }
+
+    pub fn record_histogram(&mut self, name: &str, labels: &[Label], value: u64) {
+        let key = Events::metric_key(name, labels);
+        if !self.histograms.contains_key(&key) {
+            let histogram = match self.histogram_configs.get(name) {
+                Some(&(ref buckets, ref quantiles)) => Histogram::with_bounds(buckets.clone(), quantiles.clone()),
+                None => Histogram::new()
+            };
+            self.histograms.insert(key.clone(), histogram);
+        }
+        self.histograms.get_mut(&key).unwrap().record(value);
+    }
+
+    /// Applies a sample received over the channel.
+    pub fn apply(&mut self, sample: Sample) {
+        match sample {
+            Sample::Counter { name, labels, value } => self.record_counter(&name, &labels, value),
+            Sample::Gauge { name, labels, value } => self.record_gauge(&name, &labels, value),
+            Sample::Histogram { name, labels, value } => self.record_histogram(&name, &labels, value),
+            Sample::ConfigureHistogram { name, buckets, quantiles } => self.configure_histogram(&name, buckets, quantiles)
+        }
+    }
+
+    pub fn counters(&self) -> &HashMap<String, Counter> {
+        &self.counters
+    }
+
+    pub fn gauges(&self) -> &HashMap<String, Gauge> {
+        &self.gauges
+    }
+
+    pub fn histograms(&self) -> &HashMap<String, Histogram> {
+        &self.histograms
+    }
+
+    /// Bucket/quantile configuration registered by `configure_histogram`,
+    /// keyed by metric name -- including configuration for names that
+    /// haven't had a matching histogram materialized yet.
+    pub fn histogram_configs(&self) -> &HashMap<String, (Vec<u64>, Vec<f64>)> {
+        &self.histogram_configs
+    }
+
+    /// Serializes the current counters, gauges and histogram quantiles to a
+    /// JSON batch ready to be transmitted to the telemetry server.
+    pub fn to_json(&self) -> String {
+        let (counters, gauges, histograms) = self.metric_fragments();
+        Events::envelope(&self.client_id, &self.info, &counters, &gauges, &histograms)
+    }
+
+    /// Same as `to_json`, but split into multiple self-contained JSON
+    /// batches, each no larger than `max_bytes` of fragment content. Unlike
+    /// slicing the serialized string, every returned batch is valid JSON on
+    /// its own -- metrics are never split mid-entry -- so the server can
+    /// parse and ingest each one independently. A single metric larger than
+    /// `max_bytes` still gets its own batch rather than being dropped.
+    pub fn to_json_chunks(&self, max_bytes: usize) -> Vec<String> {
+        let (counters, gauges, histograms) = self.metric_fragments();
+
+        let mut entries = Vec::new();
+        entries.extend(counters.into_iter().map(MetricFragment::Counter));
+        entries.extend(gauges.into_iter().map(MetricFragment::Gauge));
+        entries.extend(histograms.into_iter().map(MetricFragment::Histogram));
+
+        if entries.is_empty() {
+            return vec![Events::envelope(&self.client_id, &self.info, &[], &[], &[])];
+        }
+
+        let mut chunks = Vec::new();
+        let mut current = Vec::new();
+        let mut current_size = 0;
+
+        for entry in entries {
+            if !current.is_empty() && current_size + entry.len() > max_bytes {
+                chunks.push(Events::envelope_fragments(&self.client_id, &self.info, &current));
+                current = Vec::new();
+                current_size = 0;
+            }
+            current_size += entry.len();
+            current.push(entry);
+        }
+        if !current.is_empty() {
+            chunks.push(Events::envelope_fragments(&self.client_id, &self.info, &current));
+        }
+
+        chunks
+    }
+
+    /// Builds the per-metric JSON fragments (not yet wrapped in an
+    /// envelope) for every counter, gauge and histogram currently held.
+    fn metric_fragments(&self) -> (Vec<String>, Vec<String>, Vec<String>) {
+        let mut counters = Vec::new();
+        for (key, counter) in &self.counters {
+            counters.push(format!("{{\"name\":{},\"value\":{}}}", json_string(key), counter.value));
+        }
+
+        let mut gauges = Vec::new();
+        for (key, gauge) in &self.gauges {
+            gauges.push(format!("{{\"name\":{},\"value\":{}}}", json_string(key), gauge.value));
+        }
+
+        let mut histograms = Vec::new();
+        for (key, histogram) in &self.histograms {
+            let quantiles: Vec<String> = histogram.quantiles().iter()
+                .map(|q| format!("\"p{}\":{}", (q * 100.0).round() as u64, histogram.value_at_quantile(*q)))
+                .collect();
+
+            let buckets = match histogram.cumulative_bucket_counts() {
+                Some(counts) => {
+                    let entries: Vec<String> = counts.iter()
+                        .map(|&(bound, count)| format!("{{\"le\":{},\"count\":{}}}", bound, count))
+                        .collect();
+                    format!(",\"buckets\":[{}]", entries.join(","))
+                }
+                None => String::new()
+            };
+
+            histograms.push(format!("{{\"name\":{},\"count\":{},{}{}}}",
+                                     json_string(key), histogram.total_count(), quantiles.join(","), buckets));
+        }
+
+        (counters, gauges, histograms)
+    }
+
+    /// Wraps pre-built counter/gauge/histogram fragments in the envelope
+    /// common to every batch (client and app identity).
+    fn envelope(client_id: &str, info: &EventInfo, counters: &[String], gauges: &[String],
+                histograms: &[String]) -> String {
+        format!("{{\"client_id\":{},\"app_name\":{},\"app_version\":{},\"counters\":[{}],\"gauges\":[{}],\"histograms\":[{}]}}",
+                json_string(client_id), json_string(&info.app_name), json_string(&info.app_version),
+                counters.join(","), gauges.join(","), histograms.join(","))
+    }
+
+    /// Same as `envelope`, but takes a single list of fragments still
+    /// tagged with their metric kind, as produced by `to_json_chunks`.
+    fn envelope_fragments(client_id: &str, info: &EventInfo, fragments: &[MetricFragment]) -> String {
+        let mut counters = Vec::new();
+        let mut gauges = Vec::new();
+        let mut histograms = Vec::new();
+
+        for fragment in fragments {
+            match *fragment {
+                MetricFragment::Counter(ref json) => counters.push(json.clone()),
+                MetricFragment::Gauge(ref json) => gauges.push(json.clone()),
+                MetricFragment::Histogram(ref json) => histograms.push(json.clone())
+            }
+        }
+
+        Events::envelope(client_id, info, &counters, &gauges, &histograms)
+    }
+}
+
+/// A single metric's JSON fragment, tagged with its kind so a chunk built
+/// from a mixed bag of fragments can be re-grouped into the right
+/// `counters`/`gauges`/`histograms` array.
+enum MetricFragment {
+    Counter(String),
+    Gauge(String),
+    Histogram(String)
+}
+
+impl MetricFragment {
+    fn len(&self) -> usize {
+        match *self {
+            MetricFragment::Counter(ref json) => json.len(),
+            MetricFragment::Gauge(ref json) => json.len(),
+            MetricFragment::Histogram(ref json) => json.len()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Events;
+    use test_support::event_info;
+
+    #[test]
+    fn to_json_chunks_splits_into_multiple_self_contained_batches() {
+        let mut events = Events::new(event_info(), "client".to_string());
+        for i in 0..20 {
+            events.record_counter(&format!("metric_{}", i), &[], i);
+        }
+
+        let chunks = events.to_json_chunks(64);
+
+        assert!(chunks.len() > 1, "expected more than one chunk, got {}", chunks.len());
+        for chunk in &chunks {
+            assert!(chunk.starts_with("{\"client_id\":"));
+            assert!(chunk.ends_with('}'));
+        }
+
+        let total_values: usize = chunks.iter().map(|c| c.matches("\"value\":").count()).sum();
+        assert_eq!(total_values, 20);
+    }
+
+    #[test]
+    fn to_json_chunks_never_returns_empty_for_empty_events() {
+        let events = Events::new(event_info(), "client".to_string());
+        let chunks = events.to_json_chunks(64);
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].contains("\"counters\":[]"));
+    }
+}
+
+/// Escapes a string for inclusion as a JSON string literal.
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            _ => escaped.push(c)
+        }
+    }
+    escaped.push('"');
+    escaped
+}
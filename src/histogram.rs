@@ -0,0 +1,276 @@
+//! Histograms aggregate sample observations (timings, sizes, etc.) into a
+//! bounded number of buckets so recording stays O(1) in time and memory
+//! regardless of how many samples come in. Two bucketing strategies are
+//! supported: the default HDR-style logarithmic bucketing, and explicit
+//! caller-supplied bucket upper bounds (Prometheus-style).
+
+use std::cmp::Ordering;
+
+/// Number of bits of precision kept within each binary order of magnitude
+/// for the default HDR-style strategy. Higher values mean finer-grained
+/// buckets at the cost of more memory.
+const DEFAULT_SUBBUCKET_BITS: u32 = 4;
+
+/// Quantiles computed when a histogram isn't configured with its own.
+const DEFAULT_QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+enum BucketStrategy {
+    Hdr { subbucket_bits: u32, subbucket_count: u64 },
+    Explicit { bounds: Vec<u64> }
+}
+
+pub struct Histogram {
+    strategy: BucketStrategy,
+    buckets: Vec<u64>,
+    zero_count: u64,
+    total_count: u64,
+    quantiles: Vec<f64>
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram::with_precision(DEFAULT_SUBBUCKET_BITS)
+    }
+
+    /// Creates a histogram with `subbucket_bits` bits of sub-bucket
+    /// precision. Typical values are 3-4.
+    pub fn with_precision(subbucket_bits: u32) -> Histogram {
+        let subbucket_count = 1u64 << subbucket_bits;
+        // One bucket row per possible exponent (0..64), each holding
+        // `subbucket_count` sub-buckets.
+        let bucket_slots = 64usize << subbucket_bits;
+
+        Histogram {
+            strategy: BucketStrategy::Hdr { subbucket_bits: subbucket_bits, subbucket_count: subbucket_count },
+            buckets: vec![0u64; bucket_slots],
+            zero_count: 0,
+            total_count: 0,
+            quantiles: DEFAULT_QUANTILES.to_vec()
+        }
+    }
+
+    /// Creates a histogram with explicit bucket upper bounds (e.g. latency
+    /// buckets `[5, 10, 25, 100, ...]`) instead of logarithmic bucketing,
+    /// and the quantiles that should be computed at flush time. `bounds`
+    /// and `quantiles` are sorted and de-duplicated; an empty `quantiles`
+    /// falls back to the defaults (p50/p90/p99).
+    pub fn with_bounds(mut bounds: Vec<u64>, mut quantiles: Vec<f64>) -> Histogram {
+        bounds.sort();
+        bounds.dedup();
+        quantiles = normalize_quantiles(quantiles);
+
+        // One bucket per bound, plus an overflow bucket for values above
+        // the last bound.
+        let bucket_slots = bounds.len() + 1;
+
+        Histogram {
+            strategy: BucketStrategy::Explicit { bounds: bounds },
+            buckets: vec![0u64; bucket_slots],
+            zero_count: 0,
+            total_count: 0,
+            quantiles: quantiles
+        }
+    }
+
+    /// Rebuilds a logarithmically-bucketed histogram from previously
+    /// persisted raw state.
+    pub fn from_hdr_parts(subbucket_bits: u32, buckets: Vec<u64>, zero_count: u64, total_count: u64,
+                           quantiles: Vec<f64>) -> Histogram {
+        Histogram {
+            strategy: BucketStrategy::Hdr { subbucket_bits: subbucket_bits, subbucket_count: 1u64 << subbucket_bits },
+            buckets: buckets,
+            zero_count: zero_count,
+            total_count: total_count,
+            quantiles: normalize_quantiles(quantiles)
+        }
+    }
+
+    /// Rebuilds an explicit-bounds histogram from previously persisted raw
+    /// state.
+    pub fn from_explicit_parts(bounds: Vec<u64>, buckets: Vec<u64>, zero_count: u64, total_count: u64,
+                                quantiles: Vec<f64>) -> Histogram {
+        Histogram {
+            strategy: BucketStrategy::Explicit { bounds: bounds },
+            buckets: buckets,
+            zero_count: zero_count,
+            total_count: total_count,
+            quantiles: normalize_quantiles(quantiles)
+        }
+    }
+
+    /// Records a single observation.
+    pub fn record(&mut self, value: u64) {
+        self.total_count += 1;
+        if value == 0 {
+            self.zero_count += 1;
+            return;
+        }
+        let index = self.bucket_index(value);
+        self.buckets[index] += 1;
+    }
+
+    pub fn total_count(&self) -> u64 {
+        self.total_count
+    }
+
+    pub fn zero_count(&self) -> u64 {
+        self.zero_count
+    }
+
+    pub fn raw_buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    /// The quantiles this histogram computes at flush time.
+    pub fn quantiles(&self) -> &[f64] {
+        &self.quantiles
+    }
+
+    /// `Some(subbucket_bits)` for an HDR-style histogram, `None` for one
+    /// configured with explicit bounds.
+    pub fn subbucket_bits(&self) -> Option<u32> {
+        match self.strategy {
+            BucketStrategy::Hdr { subbucket_bits, .. } => Some(subbucket_bits),
+            BucketStrategy::Explicit { .. } => None
+        }
+    }
+
+    /// `Some(bounds)` for a histogram configured with explicit bounds,
+    /// `None` for the default HDR-style one.
+    pub fn explicit_bounds(&self) -> Option<&[u64]> {
+        match self.strategy {
+            BucketStrategy::Explicit { ref bounds } => Some(bounds),
+            BucketStrategy::Hdr { .. } => None
+        }
+    }
+
+    /// Prometheus-style cumulative bucket counts, `(upper_bound,
+    /// cumulative_count)` pairs. Only meaningful for a histogram configured
+    /// with explicit bounds; `None` otherwise.
+    pub fn cumulative_bucket_counts(&self) -> Option<Vec<(u64, u64)>> {
+        match self.strategy {
+            BucketStrategy::Explicit { ref bounds } => {
+                let mut cumulative = self.zero_count;
+                let mut counts = Vec::with_capacity(bounds.len());
+                for (index, &bound) in bounds.iter().enumerate() {
+                    cumulative += self.buckets[index];
+                    counts.push((bound, cumulative));
+                }
+                Some(counts)
+            }
+            BucketStrategy::Hdr { .. } => None
+        }
+    }
+
+    /// Returns the representative value of the bucket holding the
+    /// `quantile`th observation (0.0 - 1.0), e.g. 0.99 for p99.
+    pub fn value_at_quantile(&self, quantile: f64) -> u64 {
+        if self.total_count == 0 {
+            return 0;
+        }
+        let target = (quantile * self.total_count as f64).ceil() as u64;
+        let mut cumulative = self.zero_count;
+        if cumulative >= target {
+            return 0;
+        }
+        for (index, &count) in self.buckets.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            cumulative += count;
+            if cumulative >= target {
+                return self.bucket_lower_bound(index);
+            }
+        }
+        0
+    }
+
+    fn bucket_index(&self, value: u64) -> usize {
+        match self.strategy {
+            BucketStrategy::Hdr { subbucket_bits, subbucket_count } => {
+                if value < subbucket_count {
+                    return value as usize;
+                }
+                let exponent = 63 - value.leading_zeros();
+                let shift = exponent.saturating_sub(subbucket_bits);
+                let sub_index = (value >> shift) & (subbucket_count - 1);
+                ((exponent as usize) << subbucket_bits) + sub_index as usize
+            }
+            BucketStrategy::Explicit { ref bounds } => {
+                bounds.iter().position(|&bound| value <= bound).unwrap_or(bounds.len())
+            }
+        }
+    }
+
+    fn bucket_lower_bound(&self, index: usize) -> u64 {
+        match self.strategy {
+            BucketStrategy::Hdr { subbucket_bits, subbucket_count } => {
+                let index = index as u64;
+                if index < subbucket_count {
+                    return index;
+                }
+                let exponent = (index >> subbucket_bits) as u32;
+                let sub_index = index & (subbucket_count - 1);
+                let shift = exponent.saturating_sub(subbucket_bits);
+                (1u64 << exponent) + (sub_index << shift)
+            }
+            BucketStrategy::Explicit { ref bounds } => {
+                if index < bounds.len() {
+                    bounds[index]
+                } else {
+                    *bounds.last().unwrap_or(&0)
+                }
+            }
+        }
+    }
+}
+
+/// Sorts and de-duplicates a quantile list, falling back to the defaults
+/// (p50/p90/p99) if it ends up empty.
+fn normalize_quantiles(mut quantiles: Vec<f64>) -> Vec<f64> {
+    quantiles.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    quantiles.dedup_by(|a, b| (*a - *b).abs() < ::std::f64::EPSILON);
+    if quantiles.is_empty() {
+        return DEFAULT_QUANTILES.to_vec();
+    }
+    quantiles
+}
+
+#[cfg(test)]
+mod test {
+    use super::Histogram;
+
+    #[test]
+    fn zero_values_are_tracked_separately() {
+        let mut h = Histogram::new();
+        h.record(0);
+        h.record(0);
+        assert_eq!(h.total_count(), 2);
+        assert_eq!(h.value_at_quantile(1.0), 0);
+    }
+
+    #[test]
+    fn quantiles_are_approximately_correct() {
+        let mut h = Histogram::new();
+        for v in 1..1001u64 {
+            h.record(v);
+        }
+        let p50 = h.value_at_quantile(0.5);
+        assert!(p50 > 450 && p50 < 550, "p50 was {}", p50);
+        let p99 = h.value_at_quantile(0.99);
+        assert!(p99 > 950 && p99 <= 1000, "p99 was {}", p99);
+    }
+
+    #[test]
+    fn explicit_bounds_produce_cumulative_bucket_counts() {
+        let mut h = Histogram::with_bounds(vec![10, 20, 30], vec![0.5]);
+        h.record(5);
+        h.record(15);
+        h.record(15);
+        h.record(25);
+        h.record(1000);
+
+        let counts = h.cumulative_bucket_counts().expect("explicit histogram has bucket counts");
+        assert_eq!(counts, vec![(10, 1), (20, 3), (30, 4)]);
+    }
+}
@@ -0,0 +1,19 @@
+#[macro_use]
+extern crate log;
+extern crate time;
+extern crate rand;
+extern crate flate2;
+#[macro_use]
+extern crate hyper;
+extern crate crossbeam_channel;
+
+pub mod controller;
+pub mod logger;
+pub mod events;
+pub mod metrics_worker;
+pub mod histogram;
+pub mod persistence;
+pub mod transmit;
+pub mod consent;
+#[cfg(test)]
+mod test_support;
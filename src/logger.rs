@@ -0,0 +1,35 @@
+use log::LogLevelFilter;
+use std::sync::{Once, ONCE_INIT};
+
+/// Thin wrapper around the `log` crate so the rest of the controller doesn't
+/// need to depend on it directly.
+pub struct MetricsLogger;
+
+impl MetricsLogger {
+    pub fn log(&self, level: LogLevelFilter, message: &str) {
+        match level {
+            LogLevelFilter::Error => error!("{}", message),
+            LogLevelFilter::Warn => warn!("{}", message),
+            LogLevelFilter::Info => info!("{}", message),
+            LogLevelFilter::Debug => debug!("{}", message),
+            _ => trace!("{}", message)
+        }
+    }
+}
+
+/// Hands out the single, process-wide `MetricsLogger` instance.
+pub struct MetricsLoggerFactory;
+
+static INIT: Once = ONCE_INIT;
+static mut LOGGER: *const MetricsLogger = 0 as *const MetricsLogger;
+
+impl MetricsLoggerFactory {
+    pub fn get_logger() -> &'static MetricsLogger {
+        unsafe {
+            INIT.call_once(|| {
+                LOGGER = Box::into_raw(Box::new(MetricsLogger));
+            });
+            &*LOGGER
+        }
+    }
+}
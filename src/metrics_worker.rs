@@ -0,0 +1,107 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use crossbeam_channel::Receiver;
+use events::{Events, Sample};
+use logger::MetricsLoggerFactory;
+use logger::MetricsLogger;
+use log::LogLevelFilter;
+use persistence;
+use transmit::{self, TransmitConfig};
+
+#[allow(non_upper_case_globals)]
+const logger: fn() -> &'static MetricsLogger = MetricsLoggerFactory::get_logger;
+
+/// Runs on its own thread and exclusively owns the aggregated `Events`.
+/// Application threads never touch this state directly -- they push
+/// `Sample`s over `samples` instead, so recording never blocks on the
+/// worker aggregating or transmitting.
+pub struct MetricsWorker {
+    quit_tx: Sender<()>,
+    handle: Option<JoinHandle<()>>
+}
+
+impl MetricsWorker {
+    pub fn new(mut events: Events, samples: Receiver<Sample>, persistence_path: PathBuf,
+               transmit_config: TransmitConfig) -> MetricsWorker {
+        let (quit_tx, quit_rx) = channel();
+        let flush_interval = Duration::from_millis(transmit_config.flush_interval_ms);
+
+        let handle = thread::spawn(move || {
+            loop {
+                // Waiting on the quit channel itself (instead of sleeping
+                // and polling it afterwards) means `quit()` wakes the
+                // worker immediately rather than blocking the caller for
+                // up to a full flush interval.
+                match quit_rx.recv_timeout(flush_interval) {
+                    Ok(_) | Err(RecvTimeoutError::Disconnected) => break,
+                    Err(RecvTimeoutError::Timeout) => {}
+                }
+
+                MetricsWorker::drain_samples(&mut events, &samples);
+                MetricsWorker::flush(&events, &persistence_path, &transmit_config);
+            }
+        });
+
+        MetricsWorker {
+            quit_tx: quit_tx,
+            handle: Some(handle)
+        }
+    }
+
+    /// Applies every sample currently sitting in the channel to `events`
+    /// without blocking for more to arrive.
+    fn drain_samples(events: &mut Events, samples: &Receiver<Sample>) {
+        for sample in samples.try_iter() {
+            events.apply(sample);
+        }
+    }
+
+    /// Persists the current metrics and transmits them to the telemetry
+    /// server.
+    fn flush(events: &Events, persistence_path: &PathBuf, transmit_config: &TransmitConfig) {
+        logger().log(LogLevelFilter::Debug,
+                      &format!("Flushing {} counters, {} gauges, {} histograms",
+                               events.counters().len(), events.gauges().len(), events.histograms().len()));
+
+        if let Err(e) = persistence::save(events, persistence_path) {
+            logger().log(LogLevelFilter::Warn, &format!("Failed to persist metrics: {}", e));
+        }
+
+        transmit::transmit(transmit_config, events);
+    }
+
+    /// Stops the worker thread, blocking until it has shut down. Any
+    /// samples still sitting in the channel are dropped along with it.
+    pub fn quit(&mut self) {
+        let _ = self.quit_tx.send(());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MetricsWorker;
+    use crossbeam_channel;
+    use events::{Events, Sample};
+    use test_support::event_info;
+
+    #[test]
+    fn drain_samples_applies_everything_sitting_in_the_channel_without_blocking() {
+        let mut events = Events::new(event_info(), "client".to_string());
+        let (tx, rx) = crossbeam_channel::unbounded();
+
+        tx.send(Sample::Counter { name: "requests".to_string(), labels: vec![], value: 3 }).unwrap();
+        tx.send(Sample::Counter { name: "requests".to_string(), labels: vec![], value: 4 }).unwrap();
+        tx.send(Sample::Gauge { name: "queue_depth".to_string(), labels: vec![], value: 2.0 }).unwrap();
+
+        MetricsWorker::drain_samples(&mut events, &rx);
+
+        assert_eq!(events.counters().get("requests").map(|c| c.value), Some(7));
+        assert_eq!(events.gauges().get("queue_depth").map(|g| g.value), Some(2.0));
+    }
+}
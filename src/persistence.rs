@@ -0,0 +1,234 @@
+//! Persists the in-memory metrics store to disk between runs. Writes are
+//! gzip-compressed and go through a temp-file-then-rename so a crash
+//! mid-write can never leave a corrupt store behind.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use flate2::read::GzDecoder;
+
+use controller::EventInfo;
+use events::{Counter, Events, Gauge};
+use histogram::Histogram;
+use logger::MetricsLoggerFactory;
+use logger::MetricsLogger;
+use log::LogLevelFilter;
+
+#[allow(non_upper_case_globals)]
+const logger: fn() -> &'static MetricsLogger = MetricsLoggerFactory::get_logger;
+
+/// Default on-disk location of the persisted metrics store, scoped to the
+/// host application's identity so two apps (or two controllers) sharing a
+/// working directory don't read and clobber each other's metrics.
+pub fn default_path(app_name: &str, app_build_id: &str) -> PathBuf {
+    PathBuf::from(format!("metrics_data_{}_{}.gz", sanitize(app_name), sanitize(app_build_id)))
+}
+
+/// Replaces anything that isn't alphanumeric, `-` or `_` with `_`, so an
+/// app name/build id can be safely embedded in a filename.
+fn sanitize(component: &str) -> String {
+    component.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// Serializes the counters/gauges/histograms currently held by `events` and
+/// atomically writes them, gzip-compressed, to `path`.
+pub fn save(events: &Events, path: &Path) -> io::Result<()> {
+    let payload = serialize(events);
+
+    // Write to a sibling temp file first; the final rename is atomic so a
+    // reader never observes a partially-written store.
+    let tmp_path = path.with_extension("tmp");
+    {
+        let file = try!(File::create(&tmp_path));
+        let mut encoder = GzEncoder::new(file, Compression::Default);
+        try!(encoder.write_all(payload.as_bytes()));
+        try!(encoder.finish());
+    }
+    fs::rename(&tmp_path, path)
+}
+
+/// Reads back a previously persisted store, if one exists at `path`.
+pub fn load(info: EventInfo, client_id: String, path: &Path) -> io::Result<Option<Events>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let file = try!(File::open(path));
+    let mut decoder = try!(GzDecoder::new(file));
+    let mut contents = String::new();
+    try!(decoder.read_to_string(&mut contents));
+    Ok(Some(deserialize(info, client_id, &contents)))
+}
+
+/// Removes the persisted store. A missing file is not an error.
+pub fn delete(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Ok(_) => Ok(()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e)
+    }
+}
+
+fn serialize(events: &Events) -> String {
+    let mut out = String::new();
+
+    for (key, counter) in events.counters() {
+        out.push_str(&format!("COUNTER\t{}\t{}\n", key, counter.value));
+    }
+    for (key, gauge) in events.gauges() {
+        out.push_str(&format!("GAUGE\t{}\t{}\n", key, gauge.value));
+    }
+    for (key, histogram) in events.histograms() {
+        let buckets: Vec<String> = histogram.raw_buckets().iter().map(|b| b.to_string()).collect();
+        let quantiles: Vec<String> = histogram.quantiles().iter().map(|q| q.to_string()).collect();
+
+        let (kind, params) = match histogram.explicit_bounds() {
+            Some(bounds) => {
+                let bounds: Vec<String> = bounds.iter().map(|b| b.to_string()).collect();
+                ("explicit", bounds.join(","))
+            }
+            None => ("hdr", histogram.subbucket_bits().unwrap_or(4).to_string())
+        };
+
+        out.push_str(&format!("HIST\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                               key, kind, params, histogram.zero_count(),
+                               histogram.total_count(), quantiles.join(","), buckets.join(",")));
+    }
+    for (name, &(ref buckets, ref quantiles)) in events.histogram_configs() {
+        // Persisted independently of histograms() so a configuration
+        // registered before the first matching record_histogram call
+        // survives a restart instead of reverting to default bucketing.
+        let buckets: Vec<String> = buckets.iter().map(|b| b.to_string()).collect();
+        let quantiles: Vec<String> = quantiles.iter().map(|q| q.to_string()).collect();
+        out.push_str(&format!("HISTCONFIG\t{}\t{}\t{}\n", name, buckets.join(","), quantiles.join(",")));
+    }
+
+    out
+}
+
+fn deserialize(info: EventInfo, client_id: String, contents: &str) -> Events {
+    let mut counters = HashMap::new();
+    let mut gauges = HashMap::new();
+    let mut histograms = HashMap::new();
+    let mut histogram_configs = HashMap::new();
+
+    for line in contents.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields.first() {
+            Some(&"COUNTER") if fields.len() == 3 => {
+                if let Ok(value) = fields[2].parse() {
+                    counters.insert(fields[1].to_string(), Counter::from_value(value));
+                }
+            }
+            Some(&"GAUGE") if fields.len() == 3 => {
+                if let Ok(value) = fields[2].parse() {
+                    gauges.insert(fields[1].to_string(), Gauge::from_value(value));
+                }
+            }
+            Some(&"HIST") if fields.len() == 8 => {
+                let key = fields[1];
+                let kind = fields[2];
+                let params = fields[3];
+                let zero_count = fields[4].parse().unwrap_or(0);
+                let total_count = fields[5].parse().unwrap_or(0);
+                let quantiles: Vec<f64> = fields[6].split(',').filter_map(|s| s.parse().ok()).collect();
+                let buckets: Vec<u64> = fields[7].split(',').filter_map(|s| s.parse().ok()).collect();
+
+                let histogram = match kind {
+                    "explicit" => {
+                        let bounds: Vec<u64> = params.split(',').filter_map(|s| s.parse().ok()).collect();
+                        if buckets.len() != bounds.len() + 1 {
+                            logger().log(LogLevelFilter::Warn,
+                                         &format!("Dropping corrupted persisted histogram {}: expected {} buckets, found {}",
+                                                  key, bounds.len() + 1, buckets.len()));
+                            continue;
+                        }
+                        Histogram::from_explicit_parts(bounds, buckets, zero_count, total_count, quantiles)
+                    }
+                    _ => {
+                        let subbucket_bits = params.parse().unwrap_or(4);
+                        let expected_len = 64usize << subbucket_bits;
+                        if buckets.len() != expected_len {
+                            logger().log(LogLevelFilter::Warn,
+                                         &format!("Dropping corrupted persisted histogram {}: expected {} buckets, found {}",
+                                                  key, expected_len, buckets.len()));
+                            continue;
+                        }
+                        Histogram::from_hdr_parts(subbucket_bits, buckets, zero_count, total_count, quantiles)
+                    }
+                };
+                histograms.insert(key.to_string(), histogram);
+            }
+            Some(&"HISTCONFIG") if fields.len() == 4 => {
+                let buckets: Vec<u64> = fields[2].split(',').filter_map(|s| s.parse().ok()).collect();
+                let quantiles: Vec<f64> = fields[3].split(',').filter_map(|s| s.parse().ok()).collect();
+                histogram_configs.insert(fields[1].to_string(), (buckets, quantiles));
+            }
+            _ => {}
+        }
+    }
+
+    Events::from_parts(info, client_id, counters, gauges, histograms, histogram_configs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{default_path, deserialize, serialize, sanitize};
+    use events::Events;
+    use test_support::event_info;
+
+    #[test]
+    fn serialize_then_deserialize_round_trips_counters_gauges_and_histograms() {
+        let mut events = Events::new(event_info(), "client".to_string());
+        events.record_counter("requests", &[], 5);
+        events.record_gauge("queue_depth", &[], 2.5);
+        events.record_histogram("latency", &[], 42);
+        events.record_histogram("latency", &[], 100);
+
+        let restored = deserialize(event_info(), "client".to_string(), &serialize(&events));
+
+        assert_eq!(restored.counters().get("requests").map(|c| c.value), Some(5));
+        assert_eq!(restored.gauges().get("queue_depth").map(|g| g.value), Some(2.5));
+        assert_eq!(restored.histograms().get("latency").map(|h| h.total_count()), Some(2));
+    }
+
+    #[test]
+    fn default_path_sanitizes_and_scopes_by_app_identity() {
+        let path = default_path("my app!", "2026.07/01");
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(name.contains("my_app_"));
+        assert!(name.contains("2026_07_01"));
+    }
+
+    #[test]
+    fn sanitize_replaces_unsafe_filename_characters() {
+        assert_eq!(sanitize("foo/bar baz"), "foo_bar_baz");
+    }
+
+    #[test]
+    fn deserialize_drops_a_histogram_with_a_truncated_bucket_row_instead_of_restoring_it() {
+        let row = "HIST\tlatency\thdr\t4\t0\t3\t0.5,0.9,0.99\t1,2,3\n";
+        let restored = deserialize(event_info(), "client".to_string(), row);
+        assert!(restored.histograms().get("latency").is_none());
+    }
+
+    #[test]
+    fn a_histogram_config_registered_before_its_first_record_survives_a_restart() {
+        let mut events = Events::new(event_info(), "client".to_string());
+        events.configure_histogram("latency", vec![10, 20, 30], vec![0.5, 0.9]);
+
+        // No record_histogram call landed before the "crash" -- the
+        // histogram itself was never materialized.
+        assert!(events.histograms().get("latency").is_none());
+
+        let restored = deserialize(event_info(), "client".to_string(), &serialize(&events));
+        let config = restored.histogram_configs().get("latency").expect("config should survive a restart");
+        assert_eq!(config.0, vec![10, 20, 30]);
+        assert_eq!(config.1, vec![0.5, 0.9]);
+    }
+}
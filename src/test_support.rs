@@ -0,0 +1,20 @@
+//! Shared fixtures for unit tests across the crate. Only compiled for
+//! test builds.
+
+use controller::EventInfo;
+
+/// A representative `EventInfo` for tests that don't care about any
+/// particular field value.
+pub fn event_info() -> EventInfo {
+    EventInfo::new(
+        "en-us".to_string(),
+        "linux".to_string(),
+        "1.2.3".to_string(),
+        "raspberry-pi".to_string(),
+        "app".to_string(),
+        "1.0".to_string(),
+        "default".to_string(),
+        "20160305".to_string(),
+        "rust".to_string(),
+        "arm".to_string())
+}
@@ -0,0 +1,150 @@
+//! Uploads batched events to the telemetry server: each batch is split into
+//! self-contained JSON chunks so a single request body never gets too
+//! large, gzip-compressed, and retried with exponential backoff. Every
+//! chunk carries its idempotency key as a request header so the server can
+//! dedupe a retry that follows a timed-out attempt.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use hyper::Client;
+use hyper::header::{ContentEncoding, ContentType, Encoding, Headers};
+use time;
+
+use events::Events;
+use logger::MetricsLoggerFactory;
+use logger::MetricsLogger;
+use log::LogLevelFilter;
+
+header! { (IdempotencyKey, "Idempotency-Key") => [String] }
+
+#[allow(non_upper_case_globals)]
+const logger: fn() -> &'static MetricsLogger = MetricsLoggerFactory::get_logger;
+
+/// Default limit, in bytes, on the metric content of a single uploaded
+/// chunk (the JSON envelope is not counted, and a single larger metric
+/// still gets a chunk of its own).
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+const DEFAULT_MAX_RETRIES: u32 = 5;
+const INITIAL_BACKOFF_MS: u64 = 500;
+
+/// How often, in milliseconds, the worker wakes up to drain recorded
+/// samples and snapshot/flush the aggregated metrics, absent an override.
+pub const DEFAULT_FLUSH_INTERVAL_MS: u64 = 60_000;
+
+/// Where and how batched events are transmitted, and how often the worker
+/// flushes them.
+pub struct TransmitConfig {
+    pub endpoint: String,
+    pub chunk_size: usize,
+    pub max_retries: u32,
+    pub flush_interval_ms: u64
+}
+
+impl TransmitConfig {
+    pub fn new(endpoint: String) -> TransmitConfig {
+        TransmitConfig {
+            endpoint: endpoint,
+            chunk_size: CHUNK_SIZE,
+            max_retries: DEFAULT_MAX_RETRIES,
+            flush_interval_ms: DEFAULT_FLUSH_INTERVAL_MS
+        }
+    }
+
+    pub fn clone(&self) -> TransmitConfig {
+        TransmitConfig {
+            endpoint: self.endpoint.clone(),
+            chunk_size: self.chunk_size,
+            max_retries: self.max_retries,
+            flush_interval_ms: self.flush_interval_ms
+        }
+    }
+}
+
+/// Splits `events` into self-contained JSON batches whose metric content
+/// is no larger than `config.chunk_size` (see `Events::to_json_chunks`),
+/// gzip-compresses each and uploads it with its own idempotency key.
+pub fn transmit(config: &TransmitConfig, events: &Events) {
+    for chunk in events.to_json_chunks(config.chunk_size) {
+        let key = idempotency_key(&chunk);
+        upload_with_retries(config, chunk.as_bytes(), &key);
+    }
+}
+
+/// Derives an idempotency key from the chunk contents and the current
+/// hour, so retries of the same chunk within the same window hash to the
+/// same key and can be deduped server-side.
+fn idempotency_key(chunk: &str) -> String {
+    let hour_bucket = time::now_utc().to_timespec().sec / 3600;
+
+    let mut hasher = DefaultHasher::new();
+    chunk.hash(&mut hasher);
+    hour_bucket.hash(&mut hasher);
+    format!("{:x}", hasher.finish())
+}
+
+fn upload_with_retries(config: &TransmitConfig, chunk: &[u8], key: &str) {
+    let body = gzip(chunk);
+    let client = Client::new();
+
+    let mut attempt = 0;
+    loop {
+        let mut headers = Headers::new();
+        headers.set(ContentType("application/json".parse().unwrap()));
+        headers.set(ContentEncoding(vec![Encoding::Gzip]));
+        headers.set(IdempotencyKey(key.to_string()));
+
+        let result = client.post(&config.endpoint)
+            .headers(headers)
+            .body(&body[..])
+            .send();
+
+        match result {
+            Ok(ref response) if response.status.is_success() => return,
+            Ok(ref response) => {
+                logger().log(LogLevelFilter::Debug,
+                              &format!("Telemetry chunk {} rejected with status {}", key, response.status));
+            }
+            Err(ref e) => {
+                logger().log(LogLevelFilter::Debug,
+                              &format!("Telemetry chunk {} failed to send: {}", key, e));
+            }
+        }
+
+        attempt += 1;
+        if attempt >= config.max_retries {
+            logger().log(LogLevelFilter::Warn,
+                          &format!("Giving up on telemetry chunk {} after {} attempts", key, attempt));
+            return;
+        }
+
+        thread::sleep(Duration::from_millis(INITIAL_BACKOFF_MS << (attempt - 1).min(6)));
+    }
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+    encoder.write_all(data).expect("writing to an in-memory buffer cannot fail");
+    encoder.finish().expect("writing to an in-memory buffer cannot fail")
+}
+
+#[cfg(test)]
+mod test {
+    use super::idempotency_key;
+
+    #[test]
+    fn idempotency_key_is_deterministic_for_the_same_chunk() {
+        assert_eq!(idempotency_key("{\"a\":1}"), idempotency_key("{\"a\":1}"));
+    }
+
+    #[test]
+    fn idempotency_key_differs_for_different_chunks() {
+        assert!(idempotency_key("{\"a\":1}") != idempotency_key("{\"a\":2}"));
+    }
+}